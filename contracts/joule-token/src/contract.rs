@@ -0,0 +1,713 @@
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, Address, BytesN, Env, String, Symbol, Vec,
+};
+use stellar_access::ownable::{self, Ownable};
+use stellar_contract_utils::pausable::{self, Pausable};
+use stellar_macros::{only_owner, when_not_paused};
+use stellar_tokens::fungible::Base;
+
+use crate::{oracle, timelock, QueuedOp, TimelockOp, TokenConfig};
+
+// TTL constants: extend instance storage proactively to prevent archival
+pub(crate) const TTL_THRESHOLD: u32 = 17_280; // ~1 day at 5s/ledger
+pub(crate) const TTL_EXTEND_TO: u32 = 518_400; // ~30 days
+
+// Delay between `propose_upgrade` and `execute_upgrade`
+const UPGRADE_DELAY_LEDGERS: u32 = 17_280; // ~1 day
+
+/// Storage schema this WASM expects. Bump with a new step in `run_migration`.
+/// v2: explicit `Initialized` flag.
+const SCHEMA_VERSION: u32 = 2;
+
+// ─── Storage Keys ────────────────────────────────────────────────
+
+#[contracttype]
+pub enum DataKey {
+    OracleAddress,
+    TotalMinted,
+    TotalBurned,
+    OraclePrice,
+    OracleNonce,
+    OracleLedger,
+    OraclePriceFloor,
+    OraclePriceCeiling,
+    OracleMintCap,
+    Pauser,
+    Initialized,
+    SchemaVersion,
+    PendingUpgrade,
+    TimelockDelay,
+    NextOpId,
+    QueuedOp(u64),
+    PendingOps,
+    Release(BytesN<32>),
+}
+
+/// Proposed WASM upgrade, executable from `eta_ledger`.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingUpgrade {
+    pub wasm_hash: BytesN<32>,
+    pub eta_ledger: u32,
+}
+
+// ─── Errors ──────────────────────────────────────────────────────
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum JouleError {
+    InsufficientBalance = 1,
+    InvalidAmount = 2,
+    Unauthorized = 3,
+    OracleOnly = 5,
+    AlreadyProcessed = 6,
+    StaleNonce = 7,
+    PriceOutOfBounds = 8,
+    CircuitBreakerTripped = 9,
+    MintCapExceeded = 10,
+    PriceNotSet = 11,
+    AlreadyInitialized = 12,
+    MigrationPending = 13,
+    NoMigrationPending = 14,
+    NoPendingUpgrade = 15,
+    UpgradeNotReady = 16,
+    NoQueuedOp = 17,
+    OpNotReady = 18,
+    UnknownRelease = 19,
+    ReleaseNotReady = 20,
+}
+
+// ─── Contract ────────────────────────────────────────────────────
+
+#[contract]
+pub struct JouleToken;
+
+// ─── SEP-41 Token Functions (Address-based signatures for indexer compatibility) ──
+
+// Ownable (2-step transfer)
+#[contractimpl]
+impl Ownable for JouleToken {}
+
+// Pausable (owner, or the designated pauser for pause only)
+#[contractimpl]
+impl Pausable for JouleToken {
+    fn paused(e: &Env) -> bool {
+        pausable::paused(e)
+    }
+
+    fn pause(e: &Env, caller: Address) {
+        let pauser: Option<Address> = e.storage().instance().get(&DataKey::Pauser);
+        if pauser == Some(caller.clone()) {
+            caller.require_auth();
+        } else {
+            ownable::enforce_owner_auth(e);
+        }
+        pausable::pause(e);
+    }
+
+    fn unpause(e: &Env, _caller: Address) {
+        ownable::enforce_owner_auth(e);
+        pausable::unpause(e);
+    }
+}
+
+// ─── JOULE-Specific Functions ────────────────────────────────────
+
+#[contractimpl]
+impl JouleToken {
+    // ─── SEP-41 functions (plain Address for indexer detection) ─────
+
+    pub fn allowance(env: Env, from: Address, spender: Address) -> i128 {
+        Base::allowance(&env, &from, &spender)
+    }
+
+    pub fn approve(env: Env, from: Address, spender: Address, amount: i128, expiration_ledger: u32) {
+        Base::approve(&env, &from, &spender, amount, expiration_ledger);
+    }
+
+    pub fn balance(env: Env, id: Address) -> i128 {
+        Base::balance(&env, &id)
+    }
+
+    pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+        from.require_auth();
+        Base::update(&env, Some(&from), Some(&to), amount);
+    }
+
+    pub fn transfer_from(env: Env, spender: Address, from: Address, to: Address, amount: i128) {
+        Base::transfer_from(&env, &spender, &from, &to, amount);
+    }
+
+    pub fn burn(env: Env, from: Address, amount: i128) {
+        Base::burn(&env, &from, amount);
+    }
+
+    pub fn burn_from(env: Env, spender: Address, from: Address, amount: i128) {
+        Base::burn_from(&env, &spender, &from, amount);
+    }
+
+    pub fn decimals(env: Env) -> u32 {
+        Base::decimals(&env)
+    }
+
+    pub fn name(env: Env) -> String {
+        Base::name(&env)
+    }
+
+    pub fn symbol(env: Env) -> String {
+        Base::symbol(&env)
+    }
+
+    pub fn total_supply(env: Env) -> i128 {
+        Base::total_supply(&env)
+    }
+
+    /// Deploy-time constructor: owner, oracle, metadata, price bounds and
+    /// mint cap are all set atomically with deployment.
+    pub fn __constructor(env: Env, owner: Address, oracle: Address, config: TokenConfig) {
+        assert!(config.decimals <= 18, "Invalid decimals");
+        assert!(config.price_floor > 0, "Floor must be positive");
+        assert!(config.price_ceiling > config.price_floor, "Ceiling must exceed floor");
+        assert!(config.mint_cap > 0, "Mint cap must be positive");
+        Self::init(&env, &owner, &oracle, config.decimals, config.name, config.symbol);
+
+        env.storage()
+            .instance()
+            .set(&DataKey::OraclePriceFloor, &config.price_floor);
+        env.storage()
+            .instance()
+            .set(&DataKey::OraclePriceCeiling, &config.price_ceiling);
+        env.storage()
+            .instance()
+            .set(&DataKey::OracleMintCap, &config.mint_cap);
+    }
+
+    /// Legacy initializer for instances deployed before the constructor
+    /// existed (JOULE metadata, default bounds). Rejects re-initialization.
+    pub fn initialize(env: Env, owner: Address, oracle: Address) -> Result<(), JouleError> {
+        // Pre-flag instances are recognised by their stored oracle.
+        let storage = env.storage().instance();
+        if storage.has(&DataKey::Initialized) || storage.has(&DataKey::OracleAddress) {
+            return Err(JouleError::AlreadyInitialized);
+        }
+        Self::init(
+            &env,
+            &owner,
+            &oracle,
+            7,
+            String::from_str(&env, "Joule Compute Credit"),
+            String::from_str(&env, "JOULE"),
+        );
+        Ok(())
+    }
+
+    fn init(env: &Env, owner: &Address, oracle: &Address, decimals: u32, name: String, symbol: String) {
+        ownable::set_owner(env, owner);
+        Base::set_metadata(env, decimals, name, symbol);
+
+        env.storage()
+            .instance()
+            .set(&DataKey::OracleAddress, oracle);
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalMinted, &0i128);
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalBurned, &0i128);
+        env.storage().instance().set(&DataKey::Initialized, &true);
+        env.storage()
+            .instance()
+            .set(&DataKey::SchemaVersion, &SCHEMA_VERSION);
+    }
+
+    /// Stored storage schema version; instances predating versioning are v1.
+    fn stored_schema_version(env: &Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::SchemaVersion)
+            .unwrap_or(1)
+    }
+
+    /// Apply the migration step from `from` to `from + 1`.
+    fn run_migration(env: &Env, from: u32) {
+        if from == 1 {
+            // v1 instances were initialized without the flag.
+            env.storage().instance().set(&DataKey::Initialized, &true);
+        }
+    }
+
+    #[only_owner]
+    #[when_not_paused]
+    pub fn mint(env: Env, to: Address, amount: i128) {
+        assert!(amount > 0, "Amount must be positive");
+        Base::update(&env, None, Some(&to), amount);
+
+        let total: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalMinted)
+            .unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalMinted, &(total + amount));
+
+        env.events()
+            .publish((Symbol::new(&env, "mint"),), (to, amount));
+    }
+
+    #[when_not_paused]
+    pub fn burn_for_compute(env: Env, from: Address, amount: i128) {
+        from.require_auth();
+        assert!(amount > 0, "Amount must be positive");
+        Base::update(&env, Some(&from), None, amount);
+
+        let total: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalBurned)
+            .unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalBurned, &(total + amount));
+
+        env.events()
+            .publish((Symbol::new(&env, "burn_for_compute"),), (from, amount));
+    }
+
+    /// Owner queues an oracle change behind the timelock. Returns the op id.
+    #[only_owner]
+    pub fn set_oracle(env: Env, oracle: Address) -> u64 {
+        Self::queue_op(&env, TimelockOp::SetOracle(oracle))
+    }
+
+    pub fn oracle(env: Env) -> Address {
+        env.storage()
+            .instance()
+            .get(&DataKey::OracleAddress)
+            .expect("Oracle not set")
+    }
+
+    pub fn total_minted(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::TotalMinted)
+            .unwrap_or(0)
+    }
+
+    pub fn total_burned(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::TotalBurned)
+            .unwrap_or(0)
+    }
+
+    pub fn circulating_supply(env: Env) -> i128 {
+        let minted: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalMinted)
+            .unwrap_or(0);
+        let burned: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalBurned)
+            .unwrap_or(0);
+        minted - burned
+    }
+
+    // ─── Oracle Price Feed ──────────────────────────────────────
+
+    /// Oracle posts JOULE_USD price. Validates nonce, bounds, circuit breaker.
+    pub fn set_price(env: Env, price_scaled: i128, nonce: u64) -> Result<(), JouleError> {
+        let oracle_addr: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::OracleAddress)
+            .expect("Oracle not set");
+        oracle_addr.require_auth();
+        env.storage().instance().extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+
+        // Nonce must be strictly increasing
+        let current_nonce = oracle::get_nonce(&env);
+        if nonce <= current_nonce {
+            return Err(JouleError::StaleNonce);
+        }
+
+        // Price must be within bounds
+        oracle::check_bounds(&env, price_scaled)?;
+
+        // Circuit breaker: if there's an existing price, check swing
+        if let Some(existing) = oracle::get_price_data(&env) {
+            oracle::check_circuit_breaker(existing.price, price_scaled)?;
+        }
+
+        let data = oracle::PriceData {
+            price: price_scaled,
+            nonce,
+            ledger: env.ledger().sequence(),
+        };
+        oracle::set_price_data(&env, &data);
+
+        env.events().publish(
+            (Symbol::new(&env, "price_updated"),),
+            (price_scaled, nonce, env.ledger().sequence()),
+        );
+
+        Ok(())
+    }
+
+    /// Returns (price_scaled, last_updated_ledger). Panics if no price set.
+    pub fn get_price(env: Env) -> (i128, u32) {
+        let data = oracle::get_price_data(&env).expect("Price not set");
+        (data.price, data.ledger)
+    }
+
+    /// Last accepted price nonce (0 before the first update).
+    pub fn nonce(env: Env) -> u64 {
+        oracle::get_nonce(&env)
+    }
+
+    /// Oracle mints JOULE up to mint_cap. Respects pause.
+    #[when_not_paused]
+    pub fn oracle_mint(env: Env, to: Address, amount: i128) -> Result<(), JouleError> {
+        let oracle_addr: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::OracleAddress)
+            .expect("Oracle not set");
+        oracle_addr.require_auth();
+        env.storage().instance().extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+
+        if amount <= 0 {
+            return Err(JouleError::InvalidAmount);
+        }
+
+        let cap = oracle::get_mint_cap(&env);
+        if amount > cap {
+            return Err(JouleError::MintCapExceeded);
+        }
+
+        Base::update(&env, None, Some(&to), amount);
+
+        let total: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalMinted)
+            .unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalMinted, &(total + amount));
+
+        env.events()
+            .publish((Symbol::new(&env, "oracle_mint"),), (to, amount));
+
+        Ok(())
+    }
+
+    /// Owner queues a price override behind the timelock — skips circuit
+    /// breaker once executed. The nonce is assigned at execution, so oracle
+    /// updates while it waits don't make it stale. Returns the op id.
+    #[only_owner]
+    pub fn owner_set_price(env: Env, price_scaled: i128) -> Result<u64, JouleError> {
+        oracle::check_bounds(&env, price_scaled)?;
+        Ok(Self::queue_op(&env, TimelockOp::OwnerSetPrice(price_scaled)))
+    }
+
+    /// Owner queues a new max JOULE per oracle_mint call. Returns the op id.
+    #[only_owner]
+    pub fn set_mint_cap(env: Env, cap: i128) -> u64 {
+        assert!(cap > 0, "Mint cap must be positive");
+        Self::queue_op(&env, TimelockOp::SetMintCap(cap))
+    }
+
+    /// Read current mint cap.
+    pub fn mint_cap(env: Env) -> i128 {
+        oracle::get_mint_cap(&env)
+    }
+
+    /// Owner queues a new price floor and ceiling. Returns the op id.
+    #[only_owner]
+    pub fn set_price_bounds(env: Env, floor: i128, ceiling: i128) -> u64 {
+        assert!(floor > 0, "Floor must be positive");
+        assert!(ceiling > floor, "Ceiling must exceed floor");
+        Self::queue_op(&env, TimelockOp::SetPriceBounds(floor, ceiling))
+    }
+
+    /// Read price floor and ceiling.
+    pub fn price_bounds(env: Env) -> (i128, i128) {
+        (
+            oracle::get_price_floor(&env),
+            oracle::get_price_ceiling(&env),
+        )
+    }
+
+    // ─── Timelock ───────────────────────────────────────────────
+
+    /// Owner queues a new timelock delay; it applies to ops queued after it
+    /// executes. Returns the op id.
+    #[only_owner]
+    pub fn set_timelock_delay(env: Env, ledgers: u32) -> u64 {
+        assert!(ledgers >= timelock::MIN_DELAY_LEDGERS, "Delay too short");
+        Self::queue_op(&env, TimelockOp::SetDelay(ledgers))
+    }
+
+    /// Owner applies a queued operation once its delay has passed.
+    #[only_owner]
+    pub fn execute_op(env: Env, id: u64) -> Result<(), JouleError> {
+        let op = timelock::take_ready(&env, id)?;
+        match op.clone() {
+            TimelockOp::SetOracle(oracle) => {
+                env.storage()
+                    .instance()
+                    .set(&DataKey::OracleAddress, &oracle);
+            }
+            TimelockOp::SetPriceBounds(floor, ceiling) => {
+                env.storage()
+                    .instance()
+                    .set(&DataKey::OraclePriceFloor, &floor);
+                env.storage()
+                    .instance()
+                    .set(&DataKey::OraclePriceCeiling, &ceiling);
+            }
+            TimelockOp::SetMintCap(cap) => {
+                env.storage()
+                    .instance()
+                    .set(&DataKey::OracleMintCap, &cap);
+            }
+            TimelockOp::OwnerSetPrice(price_scaled) => {
+                // Bounds may have moved while queued
+                oracle::check_bounds(&env, price_scaled)?;
+                let nonce = oracle::get_nonce(&env) + 1;
+                let data = oracle::PriceData {
+                    price: price_scaled,
+                    nonce,
+                    ledger: env.ledger().sequence(),
+                };
+                oracle::set_price_data(&env, &data);
+
+                env.events().publish(
+                    (Symbol::new(&env, "price_override"),),
+                    (price_scaled, nonce, env.ledger().sequence()),
+                );
+            }
+            TimelockOp::SetDelay(ledgers) => timelock::set_delay(&env, ledgers),
+        }
+        env.events()
+            .publish((Symbol::new(&env, "op_executed"), id), op);
+        Ok(())
+    }
+
+    /// Owner cancels a queued operation. Exempt from the timelock.
+    #[only_owner]
+    pub fn cancel_op(env: Env, id: u64) -> Result<(), JouleError> {
+        let queued = timelock::remove(&env, id)?;
+        env.events()
+            .publish((Symbol::new(&env, "op_cancelled"), id), queued.op);
+        Ok(())
+    }
+
+    /// A queued operation, if still pending.
+    pub fn get_queued_op(env: Env, id: u64) -> Option<QueuedOp> {
+        timelock::get(&env, id)
+    }
+
+    /// All operations queued and not yet executed or cancelled.
+    pub fn pending_ops(env: Env) -> Vec<QueuedOp> {
+        let mut ops = Vec::new(&env);
+        for id in timelock::pending_ids(&env).iter() {
+            if let Some(queued) = timelock::get(&env, id) {
+                ops.push_back(queued);
+            }
+        }
+        ops
+    }
+
+    /// Current timelock delay in ledgers.
+    pub fn timelock_delay(env: Env) -> u32 {
+        timelock::get_delay(&env)
+    }
+
+    /// Owner calls that bypass the timelock for emergencies.
+    pub fn timelock_exempt(env: Env) -> Vec<Symbol> {
+        let mut names = Vec::new(&env);
+        for name in timelock::EMERGENCY_EXEMPT {
+            names.push_back(Symbol::new(&env, name));
+        }
+        names
+    }
+
+    fn queue_op(env: &Env, op: TimelockOp) -> u64 {
+        let queued = timelock::queue(env, op);
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.events().publish(
+            (Symbol::new(env, "op_queued"), queued.id),
+            (queued.op, queued.eta_ledger),
+        );
+        queued.id
+    }
+
+    /// Owner grants the pause-only role (e.g. the rebalancer, for depeg emergencies).
+    /// Unpausing stays owner-only.
+    #[only_owner]
+    pub fn set_pauser(env: Env, pauser: Address) {
+        env.storage().instance().set(&DataKey::Pauser, &pauser);
+        env.events()
+            .publish((Symbol::new(&env, "pauser_set"),), pauser);
+    }
+
+    /// Read the pauser address, if any.
+    pub fn pauser(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Pauser)
+    }
+
+    /// Owner registers a release WASM hash. Upgrades to it may be proposed
+    /// once `UPGRADE_DELAY_LEDGERS` have passed. Returns that ledger.
+    #[only_owner]
+    pub fn register_release(env: Env, wasm_hash: BytesN<32>) -> u32 {
+        let active_ledger = env.ledger().sequence() + UPGRADE_DELAY_LEDGERS;
+        let key = DataKey::Release(wasm_hash.clone());
+        env.storage().persistent().set(&key, &active_ledger);
+        env.storage()
+            .persistent()
+            .extend_ttl(&key, TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.events().publish(
+            (Symbol::new(&env, "release_registered"),),
+            (wasm_hash, active_ledger),
+        );
+        active_ledger
+    }
+
+    /// Owner removes a release hash; a pending upgrade to it can no longer execute.
+    #[only_owner]
+    pub fn revoke_release(env: Env, wasm_hash: BytesN<32>) {
+        env.storage()
+            .persistent()
+            .remove(&DataKey::Release(wasm_hash.clone()));
+        env.events()
+            .publish((Symbol::new(&env, "release_revoked"),), wasm_hash);
+    }
+
+    /// Ledger from which an upgrade to `wasm_hash` may be proposed, if registered.
+    pub fn release(env: Env, wasm_hash: BytesN<32>) -> Option<u32> {
+        env.storage().persistent().get(&DataKey::Release(wasm_hash))
+    }
+
+    /// `wasm_hash` must be a registered release whose registration delay has passed.
+    fn require_release(env: &Env, wasm_hash: &BytesN<32>) -> Result<(), JouleError> {
+        let active_ledger: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Release(wasm_hash.clone()))
+            .ok_or(JouleError::UnknownRelease)?;
+        if env.ledger().sequence() < active_ledger {
+            return Err(JouleError::ReleaseNotReady);
+        }
+        Ok(())
+    }
+
+    /// Owner proposes a WASM upgrade, executable after `UPGRADE_DELAY_LEDGERS`
+    /// so the release hash can be checked before it goes live. The hash must
+    /// be a registered release past its own delay. Replaces any earlier
+    /// proposal. Refused while a migration is still pending.
+    #[only_owner]
+    pub fn propose_upgrade(env: Env, wasm_hash: BytesN<32>) -> Result<u32, JouleError> {
+        if Self::stored_schema_version(&env) < SCHEMA_VERSION {
+            return Err(JouleError::MigrationPending);
+        }
+        Self::require_release(&env, &wasm_hash)?;
+        let pending = PendingUpgrade {
+            wasm_hash,
+            eta_ledger: env.ledger().sequence() + UPGRADE_DELAY_LEDGERS,
+        };
+        env.storage()
+            .instance()
+            .set(&DataKey::PendingUpgrade, &pending);
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.events().publish(
+            (Symbol::new(&env, "upgrade_proposed"),),
+            (pending.wasm_hash, pending.eta_ledger),
+        );
+        Ok(pending.eta_ledger)
+    }
+
+    /// Owner applies the proposed upgrade once its delay has passed.
+    #[only_owner]
+    pub fn execute_upgrade(env: Env) -> Result<(), JouleError> {
+        let pending: PendingUpgrade = env
+            .storage()
+            .instance()
+            .get(&DataKey::PendingUpgrade)
+            .ok_or(JouleError::NoPendingUpgrade)?;
+        if env.ledger().sequence() < pending.eta_ledger {
+            return Err(JouleError::UpgradeNotReady);
+        }
+        if Self::stored_schema_version(&env) < SCHEMA_VERSION {
+            return Err(JouleError::MigrationPending);
+        }
+        Self::require_release(&env, &pending.wasm_hash)?;
+        env.storage().instance().remove(&DataKey::PendingUpgrade);
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.events()
+            .publish((Symbol::new(&env, "upgrade_executed"),), pending.wasm_hash.clone());
+        env.deployer().update_current_contract_wasm(pending.wasm_hash);
+        Ok(())
+    }
+
+    /// Owner withdraws the proposed upgrade.
+    #[only_owner]
+    pub fn cancel_upgrade(env: Env) -> Result<(), JouleError> {
+        let pending: PendingUpgrade = env
+            .storage()
+            .instance()
+            .get(&DataKey::PendingUpgrade)
+            .ok_or(JouleError::NoPendingUpgrade)?;
+        env.storage().instance().remove(&DataKey::PendingUpgrade);
+        env.events()
+            .publish((Symbol::new(&env, "upgrade_cancelled"),), pending.wasm_hash);
+        Ok(())
+    }
+
+    /// The proposed upgrade, if any.
+    pub fn pending_upgrade(env: Env) -> Option<PendingUpgrade> {
+        env.storage().instance().get(&DataKey::PendingUpgrade)
+    }
+
+    /// Owner runs the pending storage migrations after an upgrade, each step
+    /// exactly once, bringing the stored schema up to `version()`.
+    #[only_owner]
+    pub fn migrate(env: Env) -> Result<u32, JouleError> {
+        let from = Self::stored_schema_version(&env);
+        if from >= SCHEMA_VERSION {
+            return Err(JouleError::NoMigrationPending);
+        }
+        for step in from..SCHEMA_VERSION {
+            Self::run_migration(&env, step);
+        }
+        env.storage()
+            .instance()
+            .set(&DataKey::SchemaVersion, &SCHEMA_VERSION);
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.events()
+            .publish((Symbol::new(&env, "migrated"),), (from, SCHEMA_VERSION));
+        Ok(SCHEMA_VERSION)
+    }
+
+    /// Storage schema version this WASM expects.
+    pub fn version(_env: Env) -> u32 {
+        SCHEMA_VERSION
+    }
+
+    /// Storage schema version the instance has been migrated to.
+    pub fn schema_version(env: Env) -> u32 {
+        Self::stored_schema_version(&env)
+    }
+}
@@ -1,6 +1,6 @@
 use soroban_sdk::{contracttype, Env};
 
-use crate::{DataKey, JouleError};
+use crate::contract::{DataKey, JouleError};
 
 /// Price data stored by the oracle
 #[contracttype]
@@ -1,25 +1,45 @@
 #![cfg(test)]
 
-use soroban_sdk::{testutils::Address as _, Address, Env, String};
-
-use crate::JouleTokenClient;
+use soroban_sdk::{
+    testutils::{Address as _, Ledger},
+    Address, BytesN, Env, String, Symbol,
+};
+
+use crate::oracle::{DEFAULT_MINT_CAP, DEFAULT_PRICE_CEILING, DEFAULT_PRICE_FLOOR};
+use crate::{JouleError, JouleTokenClient, PendingUpgrade, TimelockOp, TokenConfig};
+
+fn joule_config(env: &Env) -> TokenConfig {
+    TokenConfig {
+        name: String::from_str(env, "Joule Compute Credit"),
+        symbol: String::from_str(env, "JOULE"),
+        decimals: 7,
+        price_floor: DEFAULT_PRICE_FLOOR,
+        price_ceiling: DEFAULT_PRICE_CEILING,
+        mint_cap: DEFAULT_MINT_CAP,
+    }
+}
 
 fn setup() -> (Env, JouleTokenClient<'static>, Address, Address, Address) {
     let env = Env::default();
     env.mock_all_auths();
 
-    let contract_id = env.register(crate::JouleToken, ());
-    let client = JouleTokenClient::new(&env, &contract_id);
-
     let owner = Address::generate(&env);
     let oracle = Address::generate(&env);
     let agent = Address::generate(&env);
 
-    client.initialize(&owner, &oracle);
+    let contract_id = env.register(crate::JouleToken, (&owner, &oracle, joule_config(&env)));
+    let client = JouleTokenClient::new(&env, &contract_id);
 
     (env, client, owner, oracle, agent)
 }
 
+/// Advance past the timelock delay of op `id` and execute it.
+fn run_queued(env: &Env, client: &JouleTokenClient, id: u64) {
+    let eta = client.get_queued_op(&id).unwrap().eta_ledger;
+    env.ledger().with_mut(|l| l.sequence_number = eta);
+    client.execute_op(&id);
+}
+
 // ─── Basic Token Tests ──────────────────────────────────────────
 
 #[test]
@@ -53,6 +73,41 @@ fn test_transfer_no_fee() {
     assert_eq!(client.balance(&agent), 0);
 }
 
+#[test]
+fn test_constructor_config() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let owner = Address::generate(&env);
+    let oracle = Address::generate(&env);
+    let config = TokenConfig {
+        name: String::from_str(&env, "GPU Hour Credit"),
+        symbol: String::from_str(&env, "GPUH"),
+        decimals: 6,
+        price_floor: 5_000_000,
+        price_ceiling: 50_000_000,
+        mint_cap: 1_000_000_000,
+    };
+    let contract_id = env.register(crate::JouleToken, (&owner, &oracle, config));
+    let client = JouleTokenClient::new(&env, &contract_id);
+
+    assert_eq!(client.symbol(), String::from_str(&env, "GPUH"));
+    assert_eq!(client.decimals(), 6);
+    assert_eq!(client.price_bounds(), (5_000_000, 50_000_000));
+    assert_eq!(client.mint_cap(), 1_000_000_000);
+    assert_eq!(client.oracle(), oracle);
+}
+
+#[test]
+fn test_initialize_rejects_reinit() {
+    let (env, client, _owner, oracle, _agent) = setup();
+    let attacker = Address::generate(&env);
+    assert_eq!(
+        client.try_initialize(&attacker, &attacker),
+        Err(Ok(JouleError::AlreadyInitialized))
+    );
+    assert_eq!(client.oracle(), oracle);
+}
+
 // ─── Oracle Price Feed Tests ────────────────────────────────────
 
 #[test]
@@ -165,20 +220,14 @@ fn test_oracle_mint_zero() {
 
 #[test]
 fn test_owner_set_price_skips_circuit_breaker() {
-    let (_env, client, _owner, _oracle, _agent) = setup();
+    let (env, client, _owner, _oracle, _agent) = setup();
     client.set_price(&10_000, &1_u64);
     // 50% swing — would fail set_price but owner override skips circuit breaker
-    client.owner_set_price(&15_000, &2_u64);
+    let id = client.owner_set_price(&15_000);
+    run_queued(&env, &client, id);
     let (price, _) = client.get_price();
     assert_eq!(price, 15_000);
-}
-
-#[test]
-#[should_panic(expected = "Error(Contract, #7)")]
-fn test_owner_set_price_stale_nonce() {
-    let (_env, client, _owner, _oracle, _agent) = setup();
-    client.set_price(&10_000, &5_u64);
-    client.owner_set_price(&15_000, &3_u64); // stale
+    assert_eq!(client.nonce(), 2);
 }
 
 #[test]
@@ -186,17 +235,18 @@ fn test_owner_set_price_stale_nonce() {
 fn test_owner_set_price_out_of_bounds() {
     let (_env, client, _owner, _oracle, _agent) = setup();
     // Still respects bounds
-    client.owner_set_price(&500_000, &1_u64);
+    client.owner_set_price(&500_000);
 }
 
 // ─── Configuration Tests ────────────────────────────────────────
 
 #[test]
 fn test_set_mint_cap() {
-    let (_env, client, _owner, _oracle, agent) = setup();
+    let (env, client, _owner, _oracle, agent) = setup();
     assert_eq!(client.mint_cap(), 100_000_000_000); // default
 
-    client.set_mint_cap(&50_000_000_000); // 5,000 JOULE
+    let id = client.set_mint_cap(&50_000_000_000); // 5,000 JOULE
+    run_queued(&env, &client, id);
     assert_eq!(client.mint_cap(), 50_000_000_000);
 
     // Now mint at new cap limit
@@ -206,12 +256,13 @@ fn test_set_mint_cap() {
 
 #[test]
 fn test_set_price_bounds() {
-    let (_env, client, _owner, _oracle, _agent) = setup();
+    let (env, client, _owner, _oracle, _agent) = setup();
     let (floor, ceiling) = client.price_bounds();
     assert_eq!(floor, 1_000);
     assert_eq!(ceiling, 100_000);
 
-    client.set_price_bounds(&5_000, &50_000);
+    let id = client.set_price_bounds(&5_000, &50_000);
+    run_queued(&env, &client, id);
     let (floor, ceiling) = client.price_bounds();
     assert_eq!(floor, 5_000);
     assert_eq!(ceiling, 50_000);
@@ -219,8 +270,9 @@ fn test_set_price_bounds() {
 
 #[test]
 fn test_custom_bounds_enforced() {
-    let (_env, client, _owner, _oracle, _agent) = setup();
-    client.set_price_bounds(&5_000, &50_000);
+    let (env, client, _owner, _oracle, _agent) = setup();
+    let id = client.set_price_bounds(&5_000, &50_000);
+    run_queued(&env, &client, id);
 
     // Price within new bounds works
     client.set_price(&7_630, &1_u64);
@@ -231,8 +283,9 @@ fn test_custom_bounds_enforced() {
 #[test]
 #[should_panic(expected = "Error(Contract, #8)")]
 fn test_custom_bounds_reject_below() {
-    let (_env, client, _owner, _oracle, _agent) = setup();
-    client.set_price_bounds(&5_000, &50_000);
+    let (env, client, _owner, _oracle, _agent) = setup();
+    let id = client.set_price_bounds(&5_000, &50_000);
+    run_queued(&env, &client, id);
     client.set_price(&3_000, &1_u64); // below new floor
 }
 
@@ -291,6 +344,23 @@ fn test_transfer_from_with_allowance() {
     assert_eq!(client.allowance(&agent, &spender), 200_000_000);
 }
 
+// ─── Pauser Role Tests ──────────────────────────────────────────
+
+#[test]
+fn test_pauser_can_pause() {
+    let (env, client, owner, _oracle, agent) = setup();
+    let pauser = Address::generate(&env);
+    client.set_pauser(&pauser);
+    assert_eq!(client.pauser(), Some(pauser.clone()));
+
+    client.pause(&pauser);
+    assert!(client.paused());
+    assert!(client.try_oracle_mint(&agent, &1_000_000).is_err());
+
+    client.unpause(&owner);
+    assert!(!client.paused());
+}
+
 // ─── Burn for Compute Tests ─────────────────────────────────────
 
 #[test]
@@ -302,3 +372,125 @@ fn test_burn_for_compute() {
     assert_eq!(client.total_burned(), 500_000_000);
     assert_eq!(client.circulating_supply(), 500_000_000);
 }
+
+// ─── Versioning / Migration Tests ───────────────────────────────
+
+#[test]
+fn test_migrate_v1() {
+    let (env, client, _owner, _oracle, _agent) = setup();
+    assert_eq!(client.schema_version(), client.version());
+    assert_eq!(client.try_migrate(), Err(Ok(JouleError::NoMigrationPending)));
+
+    // Rewind storage to the v1 layout
+    env.as_contract(&client.address, || {
+        env.storage().instance().remove(&crate::DataKey::SchemaVersion);
+        env.storage().instance().remove(&crate::DataKey::Initialized);
+    });
+    assert_eq!(client.schema_version(), 1);
+    let hash = BytesN::from_array(&env, &[7u8; 32]);
+    assert_eq!(client.try_propose_upgrade(&hash), Err(Ok(JouleError::MigrationPending)));
+
+    assert_eq!(client.migrate(), client.version());
+    assert_eq!(client.schema_version(), client.version());
+    env.as_contract(&client.address, || {
+        assert!(env.storage().instance().has(&crate::DataKey::Initialized));
+    });
+    assert_eq!(client.try_migrate(), Err(Ok(JouleError::NoMigrationPending)));
+}
+
+#[test]
+fn test_upgrade_proposal() {
+    let (env, client, _owner, _oracle, _agent) = setup();
+    assert_eq!(client.try_execute_upgrade(), Err(Ok(JouleError::NoPendingUpgrade)));
+
+    let hash = BytesN::from_array(&env, &[7u8; 32]);
+    assert_eq!(client.try_propose_upgrade(&hash), Err(Ok(JouleError::UnknownRelease)));
+    let active = client.register_release(&hash);
+    assert_eq!(client.release(&hash), Some(active));
+    env.ledger().with_mut(|l| l.sequence_number = active - 1);
+    assert_eq!(client.try_propose_upgrade(&hash), Err(Ok(JouleError::ReleaseNotReady)));
+    env.ledger().with_mut(|l| l.sequence_number = active);
+
+    let eta = client.propose_upgrade(&hash);
+    assert_eq!(eta, env.ledger().sequence() + 17_280);
+    assert_eq!(
+        client.pending_upgrade(),
+        Some(PendingUpgrade { wasm_hash: hash.clone(), eta_ledger: eta })
+    );
+    env.ledger().with_mut(|l| l.sequence_number = eta - 1);
+    assert_eq!(client.try_execute_upgrade(), Err(Ok(JouleError::UpgradeNotReady)));
+
+    client.cancel_upgrade();
+    assert_eq!(client.pending_upgrade(), None);
+    env.ledger().with_mut(|l| l.sequence_number = eta);
+    assert_eq!(client.try_execute_upgrade(), Err(Ok(JouleError::NoPendingUpgrade)));
+
+    // Revoking the release blocks an upgrade already proposed for it
+    let eta = client.propose_upgrade(&hash);
+    client.revoke_release(&hash);
+    assert_eq!(client.release(&hash), None);
+    env.ledger().with_mut(|l| l.sequence_number = eta);
+    assert_eq!(client.try_execute_upgrade(), Err(Ok(JouleError::UnknownRelease)));
+}
+
+// ─── Timelock Tests ─────────────────────────────────────────────
+
+#[test]
+fn test_timelock_queue_execute_cancel() {
+    let (env, client, _owner, oracle, _agent) = setup();
+    let new_oracle = Address::generate(&env);
+
+    let oracle_id = client.set_oracle(&new_oracle);
+    let cap_id = client.set_mint_cap(&1_000);
+    let pending = client.pending_ops();
+    assert_eq!(pending.len(), 2);
+    let queued = pending.get(0).unwrap();
+    assert_eq!(queued.op, TimelockOp::SetOracle(new_oracle.clone()));
+    assert_eq!(queued.eta_ledger, env.ledger().sequence() + client.timelock_delay());
+
+    // Nothing changes until the delay has passed
+    assert_eq!(client.oracle(), oracle);
+    assert_eq!(client.try_execute_op(&oracle_id), Err(Ok(JouleError::OpNotReady)));
+
+    client.cancel_op(&cap_id);
+    assert_eq!(client.get_queued_op(&cap_id), None);
+    assert_eq!(client.try_cancel_op(&cap_id), Err(Ok(JouleError::NoQueuedOp)));
+
+    run_queued(&env, &client, oracle_id);
+    assert_eq!(client.oracle(), new_oracle);
+    assert_eq!(client.mint_cap(), 100_000_000_000);
+    assert_eq!(client.pending_ops().len(), 0);
+    assert_eq!(client.try_execute_op(&oracle_id), Err(Ok(JouleError::NoQueuedOp)));
+}
+
+#[test]
+fn test_timelock_delay_change_is_delayed() {
+    let (env, client, _owner, _oracle, _agent) = setup();
+    assert_eq!(client.timelock_delay(), 17_280);
+    let id = client.set_timelock_delay(&720);
+    assert_eq!(client.timelock_delay(), 17_280);
+    run_queued(&env, &client, id);
+    assert_eq!(client.timelock_delay(), 720);
+
+    let id = client.set_mint_cap(&1_000);
+    assert_eq!(
+        client.get_queued_op(&id).unwrap().eta_ledger,
+        env.ledger().sequence() + 720
+    );
+    assert!(client
+        .timelock_exempt()
+        .contains(Symbol::new(&env, "pause")));
+}
+
+#[test]
+fn test_timelock_override_takes_next_nonce() {
+    let (env, client, _owner, _oracle, _agent) = setup();
+    let id = client.owner_set_price(&15_000);
+    // Oracle keeps posting while the override waits
+    client.set_price(&10_000, &3_u64);
+    run_queued(&env, &client, id);
+    assert_eq!(client.get_price().0, 15_000);
+    assert_eq!(client.nonce(), 4);
+    assert_eq!(client.try_set_price(&15_000, &4_u64), Err(Ok(JouleError::StaleNonce)));
+    client.set_price(&15_000, &5_u64);
+}
@@ -0,0 +1,113 @@
+use soroban_sdk::{contracttype, Address, Env, Vec};
+
+use crate::contract::{DataKey, JouleError, TTL_EXTEND_TO, TTL_THRESHOLD};
+
+/// Owner operation that only takes effect after the timelock delay.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimelockOp {
+    SetOracle(Address),
+    /// Floor, ceiling
+    SetPriceBounds(i128, i128),
+    SetMintCap(i128),
+    /// Price; the nonce is the one after the current nonce at execution
+    OwnerSetPrice(i128),
+    /// Ledgers; changing the delay is itself delayed
+    SetDelay(u32),
+}
+
+/// Queued operation, executable from `eta_ledger`.
+#[contracttype]
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueuedOp {
+    pub id: u64,
+    pub op: TimelockOp,
+    pub eta_ledger: u32,
+}
+
+// ─── Constants ──────────────────────────────────────────────────
+
+/// Default delay between queueing and executing: ~1 day at 5s/ledger
+pub const DEFAULT_DELAY_LEDGERS: u32 = 17_280;
+
+/// Shortest delay `SetDelay` accepts: ~1 hour
+pub const MIN_DELAY_LEDGERS: u32 = 720;
+
+/// Owner calls deliberately outside the timelock so an incident can be
+/// contained immediately.
+pub const EMERGENCY_EXEMPT: [&str; 4] = ["pause", "unpause", "set_pauser", "cancel_op"];
+
+// ─── Helpers ────────────────────────────────────────────────────
+
+pub fn get_delay(env: &Env) -> u32 {
+    env.storage()
+        .instance()
+        .get(&DataKey::TimelockDelay)
+        .unwrap_or(DEFAULT_DELAY_LEDGERS)
+}
+
+pub fn set_delay(env: &Env, ledgers: u32) {
+    env.storage()
+        .instance()
+        .set(&DataKey::TimelockDelay, &ledgers);
+}
+
+/// Ids of operations queued and not yet executed or cancelled.
+pub fn pending_ids(env: &Env) -> Vec<u64> {
+    env.storage()
+        .instance()
+        .get(&DataKey::PendingOps)
+        .unwrap_or(Vec::new(env))
+}
+
+pub fn get(env: &Env, id: u64) -> Option<QueuedOp> {
+    env.storage().persistent().get(&DataKey::QueuedOp(id))
+}
+
+/// Queue `op` behind the current delay.
+pub fn queue(env: &Env, op: TimelockOp) -> QueuedOp {
+    let id: u64 = env
+        .storage()
+        .instance()
+        .get(&DataKey::NextOpId)
+        .unwrap_or(0);
+    let queued = QueuedOp {
+        id,
+        op,
+        eta_ledger: env.ledger().sequence() + get_delay(env),
+    };
+    let key = DataKey::QueuedOp(id);
+    env.storage().persistent().set(&key, &queued);
+    env.storage()
+        .persistent()
+        .extend_ttl(&key, TTL_THRESHOLD, TTL_EXTEND_TO);
+
+    let mut ids = pending_ids(env);
+    ids.push_back(id);
+    env.storage().instance().set(&DataKey::PendingOps, &ids);
+    env.storage().instance().set(&DataKey::NextOpId, &(id + 1));
+    queued
+}
+
+/// Remove operation `id` from the queue, returning it.
+pub fn remove(env: &Env, id: u64) -> Result<QueuedOp, JouleError> {
+    let queued = get(env, id).ok_or(JouleError::NoQueuedOp)?;
+    env.storage().persistent().remove(&DataKey::QueuedOp(id));
+
+    let mut ids = pending_ids(env);
+    if let Some(index) = ids.first_index_of(id) {
+        ids.remove(index);
+    }
+    env.storage().instance().set(&DataKey::PendingOps, &ids);
+    Ok(queued)
+}
+
+/// Remove operation `id` from the queue once its delay has passed.
+pub fn take_ready(env: &Env, id: u64) -> Result<TimelockOp, JouleError> {
+    let queued = get(env, id).ok_or(JouleError::NoQueuedOp)?;
+    if env.ledger().sequence() < queued.eta_ledger {
+        return Err(JouleError::OpNotReady);
+    }
+    remove(env, id)?;
+    Ok(queued.op)
+}
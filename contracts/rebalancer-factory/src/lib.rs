@@ -0,0 +1,247 @@
+#![no_std]
+
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, xdr::ToXdr, Address, BytesN, Env,
+    IntoVal, Symbol, Val, Vec,
+};
+
+#[cfg(test)]
+mod test;
+
+// TTL constants: extend storage proactively to prevent archival
+const TTL_THRESHOLD: u32 = 17_280; // ~1 day at 5s/ledger
+const TTL_EXTEND_TO: u32 = 518_400; // ~30 days
+
+// ─── Storage Keys ────────────────────────────────────────────────
+
+#[contracttype]
+pub enum DataKey {
+    Admin,
+    RebalancerWasm,
+    DeploymentCount,
+    Deployment(u32),
+    DeploymentByToken(Address),
+}
+
+// ─── Errors ──────────────────────────────────────────────────────
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum FactoryError {
+    NotInitialized = 2,
+    AlreadyDeployed = 3,
+}
+
+// ─── Types ───────────────────────────────────────────────────────
+
+/// Deploy settings for a new rebalancer. Field-for-field the rebalancer's
+/// `RebalancerConfig`, which is its single constructor argument.
+#[contracttype]
+#[derive(Clone, Debug)]
+pub struct RebalancerParams {
+    pub joule_token: Address,
+    pub pool: Address,
+    pub quote_token: Address,
+    pub oracle: Address,
+    pub owner: Address,
+    pub joule_is_token0: bool,
+    pub router: Address,
+    pub pool_fee: u32,
+}
+
+// ─── Contract ────────────────────────────────────────────────────
+
+#[contract]
+pub struct RebalancerFactory;
+
+fn require_admin(env: &Env) -> Result<Address, FactoryError> {
+    let admin: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::Admin)
+        .ok_or(FactoryError::NotInitialized)?;
+    admin.require_auth();
+    Ok(admin)
+}
+
+#[contractimpl]
+impl RebalancerFactory {
+    /// Deploy-time constructor: sets the factory admin and the rebalancer WASM
+    /// hash to deploy, so there is no `initialize` call to front-run.
+    pub fn __constructor(env: Env, admin: Address, wasm_hash: BytesN<32>) {
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage()
+            .instance()
+            .set(&DataKey::RebalancerWasm, &wasm_hash);
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.events()
+            .publish((Symbol::new(&env, "factory_initialized"),), (admin, wasm_hash));
+    }
+
+    /// Admin deploys a rebalancer, passing `params` to its constructor so it is
+    /// configured atomically with deployment. One rebalancer per token; the
+    /// address is derived from the token. Returns the new contract address.
+    pub fn deploy(env: Env, params: RebalancerParams) -> Result<Address, FactoryError> {
+        require_admin(&env)?;
+        let token_key = DataKey::DeploymentByToken(params.joule_token.clone());
+        if env.storage().persistent().has(&token_key) {
+            return Err(FactoryError::AlreadyDeployed);
+        }
+
+        let wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::RebalancerWasm)
+            .ok_or(FactoryError::NotInitialized)?;
+        let salt: BytesN<32> = env
+            .crypto()
+            .sha256(&params.joule_token.clone().to_xdr(&env))
+            .into();
+        let rebalancer = env.deployer().with_current_contract(salt).deploy_v2(
+            wasm_hash,
+            (params.clone(),),
+        );
+
+        Self::record_deployment(&env, &params.joule_token, &rebalancer);
+
+        env.events().publish(
+            (Symbol::new(&env, "rebalancer_deployed"),),
+            (params.joule_token, rebalancer.clone()),
+        );
+
+        Ok(rebalancer)
+    }
+
+    /// Admin sets the WASM hash used for new deployments and batch upgrades.
+    pub fn set_wasm_hash(env: Env, wasm_hash: BytesN<32>) -> Result<(), FactoryError> {
+        require_admin(&env)?;
+        env.storage()
+            .instance()
+            .set(&DataKey::RebalancerWasm, &wasm_hash);
+        env.events()
+            .publish((Symbol::new(&env, "wasm_hash_set"),), wasm_hash);
+        Ok(())
+    }
+
+    /// Admin proposes an upgrade of the given rebalancers to the current WASM
+    /// hash; each starts its own upgrade delay. Each rebalancer checks its own
+    /// owner, so the admin must own them (or their owners must co-sign the
+    /// nested calls). Returns the count proposed.
+    pub fn batch_propose_upgrade(
+        env: Env,
+        rebalancers: Vec<Address>,
+    ) -> Result<u32, FactoryError> {
+        require_admin(&env)?;
+        let wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::RebalancerWasm)
+            .ok_or(FactoryError::NotInitialized)?;
+
+        for rebalancer in rebalancers.iter() {
+            let mut args: Vec<Val> = Vec::new(&env);
+            args.push_back(wasm_hash.clone().into_val(&env));
+            env.invoke_contract::<u32>(&rebalancer, &Symbol::new(&env, "propose_upgrade"), args);
+        }
+
+        env.events().publish(
+            (Symbol::new(&env, "batch_upgrade_proposed"),),
+            (wasm_hash, rebalancers.len()),
+        );
+
+        Ok(rebalancers.len())
+    }
+
+    /// Admin executes the proposed upgrades of the given rebalancers once their
+    /// delays have passed. Returns the count upgraded.
+    pub fn batch_execute_upgrade(
+        env: Env,
+        rebalancers: Vec<Address>,
+    ) -> Result<u32, FactoryError> {
+        require_admin(&env)?;
+        for rebalancer in rebalancers.iter() {
+            env.invoke_contract::<()>(
+                &rebalancer,
+                &Symbol::new(&env, "execute_upgrade"),
+                Vec::new(&env),
+            );
+        }
+
+        env.events()
+            .publish((Symbol::new(&env, "batch_upgraded"),), rebalancers.len());
+
+        Ok(rebalancers.len())
+    }
+
+    /// Admin upgrades the factory itself.
+    pub fn upgrade(env: Env, wasm_hash: BytesN<32>) -> Result<(), FactoryError> {
+        require_admin(&env)?;
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.deployer().update_current_contract_wasm(wasm_hash);
+        Ok(())
+    }
+
+    // ─── Views ───────────────────────────────────────────────────
+
+    pub fn admin(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Admin)
+    }
+
+    pub fn wasm_hash(env: Env) -> Option<BytesN<32>> {
+        env.storage().instance().get(&DataKey::RebalancerWasm)
+    }
+
+    /// Number of rebalancers deployed by this factory.
+    pub fn count(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::DeploymentCount)
+            .unwrap_or(0)
+    }
+
+    /// Deployed rebalancers in deployment order, `limit` starting at `start`.
+    pub fn get_deployments(env: Env, start: u32, limit: u32) -> Vec<Address> {
+        let end = Self::count(env.clone()).min(start.saturating_add(limit));
+        let mut deployments = Vec::new(&env);
+        for i in start..end {
+            if let Some(rebalancer) = env.storage().persistent().get(&DataKey::Deployment(i)) {
+                deployments.push_back(rebalancer);
+            }
+        }
+        deployments
+    }
+
+    /// Rebalancer deployed for `token`, if any.
+    pub fn get_by_token(env: Env, token: Address) -> Option<Address> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::DeploymentByToken(token))
+    }
+
+    // ─── Internal ────────────────────────────────────────────────
+
+    fn record_deployment(env: &Env, token: &Address, rebalancer: &Address) {
+        let index = Self::count(env.clone());
+        let index_key = DataKey::Deployment(index);
+        let token_key = DataKey::DeploymentByToken(token.clone());
+        env.storage().persistent().set(&index_key, rebalancer);
+        env.storage().persistent().set(&token_key, rebalancer);
+        env.storage()
+            .persistent()
+            .extend_ttl(&index_key, TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.storage()
+            .persistent()
+            .extend_ttl(&token_key, TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.storage()
+            .instance()
+            .set(&DataKey::DeploymentCount, &(index + 1));
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+    }
+}
@@ -0,0 +1,152 @@
+#![cfg(test)]
+
+use super::*;
+use soroban_sdk::testutils::Address as _;
+use soroban_sdk::{contract, contractimpl, vec, Env};
+
+// Built from source by build.rs
+mod rebalancer_wasm {
+    soroban_sdk::contractimport!(file = "../../target/fixtures/wasm32v1-none/release/rebalancer.wasm");
+}
+
+// ─── Mock rebalancer (upgrade only) ─────────────────────────────
+
+#[contracttype]
+enum MockKey {
+    Owner,
+    Proposed,
+    Wasm,
+}
+
+#[contract]
+pub struct MockRebalancer;
+
+#[contractimpl]
+impl MockRebalancer {
+    pub fn init(env: Env, owner: Address) {
+        env.storage().instance().set(&MockKey::Owner, &owner);
+    }
+
+    pub fn propose_upgrade(env: Env, wasm_hash: BytesN<32>) -> u32 {
+        let owner: Address = env.storage().instance().get(&MockKey::Owner).unwrap();
+        owner.require_auth();
+        env.storage().instance().set(&MockKey::Proposed, &wasm_hash);
+        env.ledger().sequence()
+    }
+
+    pub fn execute_upgrade(env: Env) {
+        let owner: Address = env.storage().instance().get(&MockKey::Owner).unwrap();
+        owner.require_auth();
+        let wasm_hash: BytesN<32> = env.storage().instance().get(&MockKey::Proposed).unwrap();
+        env.storage().instance().set(&MockKey::Wasm, &wasm_hash);
+    }
+
+    pub fn wasm(env: Env) -> Option<BytesN<32>> {
+        env.storage().instance().get(&MockKey::Wasm)
+    }
+}
+
+fn setup() -> (Env, RebalancerFactoryClient<'static>, Address, BytesN<32>) {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let wasm_hash = BytesN::from_array(&env, &[1u8; 32]);
+    let contract_id = env.register(RebalancerFactory, (admin.clone(), wasm_hash.clone()));
+    let client = RebalancerFactoryClient::new(&env, &contract_id);
+    (env, client, admin, wasm_hash)
+}
+
+fn rebalancer_params(env: &Env, owner: &Address) -> RebalancerParams {
+    RebalancerParams {
+        joule_token: Address::generate(env),
+        pool: Address::generate(env),
+        quote_token: Address::generate(env),
+        oracle: Address::generate(env),
+        owner: owner.clone(),
+        joule_is_token0: true,
+        router: Address::generate(env),
+        pool_fee: 3000,
+    }
+}
+
+#[test]
+fn test_constructor() {
+    let (env, client, admin, wasm_hash) = setup();
+    assert_eq!(client.admin(), Some(admin));
+    assert_eq!(client.wasm_hash(), Some(wasm_hash));
+    assert_eq!(client.count(), 0);
+    assert_eq!(client.get_deployments(&0, &10).len(), 0);
+    assert_eq!(client.get_by_token(&Address::generate(&env)), None);
+}
+
+#[test]
+fn test_deploy_real_rebalancer() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let wasm_hash = env.deployer().upload_contract_wasm(rebalancer_wasm::WASM);
+    let contract_id = env.register(RebalancerFactory, (admin.clone(), wasm_hash));
+    let client = RebalancerFactoryClient::new(&env, &contract_id);
+
+    let params = rebalancer_params(&env, &admin);
+    let rebalancer = client.deploy(&params);
+    assert_eq!(client.count(), 1);
+    assert_eq!(client.get_by_token(&params.joule_token), Some(rebalancer.clone()));
+    assert_eq!(client.get_deployments(&0, &10), vec![&env, rebalancer.clone()]);
+
+    // Configured by its constructor in the same call
+    let deployed = rebalancer_wasm::Client::new(&env, &rebalancer);
+    let config = deployed.get_config();
+    assert_eq!(config.owner, admin);
+    assert_eq!(config.joule_token, params.joule_token);
+    assert_eq!(deployed.version(), deployed.schema_version());
+
+    let mut again = rebalancer_params(&env, &admin);
+    again.joule_token = params.joule_token;
+    assert_eq!(client.try_deploy(&again), Err(Ok(FactoryError::AlreadyDeployed)));
+}
+
+#[test]
+fn test_batch_upgrade() {
+    let (env, client, admin, _) = setup();
+    let new_hash = BytesN::from_array(&env, &[2u8; 32]);
+    client.set_wasm_hash(&new_hash);
+
+    let a = env.register(MockRebalancer, ());
+    let b = env.register(MockRebalancer, ());
+    MockRebalancerClient::new(&env, &a).init(&admin);
+    MockRebalancerClient::new(&env, &b).init(&admin);
+
+    let rebalancers = vec![&env, a.clone(), b.clone()];
+    assert_eq!(client.batch_propose_upgrade(&rebalancers), 2);
+    assert_eq!(MockRebalancerClient::new(&env, &a).wasm(), None);
+    assert_eq!(client.batch_execute_upgrade(&rebalancers), 2);
+    assert_eq!(MockRebalancerClient::new(&env, &a).wasm(), Some(new_hash.clone()));
+    assert_eq!(MockRebalancerClient::new(&env, &b).wasm(), Some(new_hash));
+}
+
+#[test]
+#[should_panic]
+fn test_deploy_requires_admin() {
+    let env = Env::default();
+    let admin = Address::generate(&env);
+    let contract_id = env.register(
+        RebalancerFactory,
+        (admin.clone(), BytesN::from_array(&env, &[1u8; 32])),
+    );
+    let client = RebalancerFactoryClient::new(&env, &contract_id);
+    client.deploy(&rebalancer_params(&env, &admin));
+}
+
+#[test]
+#[should_panic]
+fn test_set_wasm_hash_requires_admin() {
+    let env = Env::default();
+    let admin = Address::generate(&env);
+    let contract_id = env.register(
+        RebalancerFactory,
+        (admin, BytesN::from_array(&env, &[1u8; 32])),
+    );
+    let client = RebalancerFactoryClient::new(&env, &contract_id);
+    client.set_wasm_hash(&BytesN::from_array(&env, &[2u8; 32]));
+}
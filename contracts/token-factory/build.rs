@@ -0,0 +1,28 @@
+//! Builds the JOULE token WASM the factory tests deploy, so they always run
+//! against the current token source instead of a stale or missing file.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=../joule-token/src");
+    println!("cargo:rerun-if-changed=../joule-token/Cargo.toml");
+    // Only host (test) builds import the WASM; the contract build itself doesn't.
+    if env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("wasm32") {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    // Must match the path `include_bytes!` reads in src/test.rs.
+    let target_dir = manifest_dir.join("../../target/fixtures");
+    let status = Command::new(env::var("CARGO").unwrap())
+        .args(["build", "--package", "joule-token", "--release", "--target", "wasm32v1-none"])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .env_remove("CARGO_TARGET_DIR")
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "building the JOULE token WASM failed");
+}
@@ -0,0 +1,206 @@
+#![no_std]
+
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, xdr::ToXdr, Address, BytesN, Env,
+    String, Symbol, Vec,
+};
+
+#[cfg(test)]
+mod test;
+
+// TTL constants: extend storage proactively to prevent archival
+const TTL_THRESHOLD: u32 = 17_280; // ~1 day at 5s/ledger
+const TTL_EXTEND_TO: u32 = 518_400; // ~30 days
+
+// ─── Storage Keys ────────────────────────────────────────────────
+
+#[contracttype]
+pub enum DataKey {
+    Admin,
+    TokenWasm,
+    DeploymentCount,
+    Deployment(u32),
+    TokenBySymbol(String),
+}
+
+// ─── Errors ──────────────────────────────────────────────────────
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum FactoryError {
+    NotInitialized = 2,
+    SymbolTaken = 3,
+}
+
+// ─── Types ───────────────────────────────────────────────────────
+
+/// The token's constructor settings, taken from `joule-token` itself.
+pub use joule_token::TokenConfig;
+
+/// Registry entry for a deployed token.
+#[contracttype]
+#[derive(Clone, Debug)]
+pub struct TokenRecord {
+    pub token: Address,
+    pub owner: Address,
+    pub oracle: Address,
+    pub config: TokenConfig,
+    pub deployed_ledger: u32,
+}
+
+// ─── Contract ────────────────────────────────────────────────────
+
+#[contract]
+pub struct TokenFactory;
+
+fn require_admin(env: &Env) -> Result<Address, FactoryError> {
+    let admin: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::Admin)
+        .ok_or(FactoryError::NotInitialized)?;
+    admin.require_auth();
+    Ok(admin)
+}
+
+#[contractimpl]
+impl TokenFactory {
+    /// Deploy-time constructor: sets the factory admin and the `JouleToken`
+    /// WASM hash to deploy, so there is no `initialize` call to front-run.
+    pub fn __constructor(env: Env, admin: Address, wasm_hash: BytesN<32>) {
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::TokenWasm, &wasm_hash);
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.events()
+            .publish((Symbol::new(&env, "factory_initialized"),), (admin, wasm_hash));
+    }
+
+    /// Admin deploys a compute credit token, passing `config` to its
+    /// constructor. Symbols are unique; the address is derived from the symbol.
+    /// Returns the new token address.
+    pub fn deploy(
+        env: Env,
+        owner: Address,
+        oracle: Address,
+        config: TokenConfig,
+    ) -> Result<Address, FactoryError> {
+        require_admin(&env)?;
+        let symbol_key = DataKey::TokenBySymbol(config.symbol.clone());
+        if env.storage().persistent().has(&symbol_key) {
+            return Err(FactoryError::SymbolTaken);
+        }
+
+        let wasm_hash: BytesN<32> = env
+            .storage()
+            .instance()
+            .get(&DataKey::TokenWasm)
+            .ok_or(FactoryError::NotInitialized)?;
+        let salt: BytesN<32> = env
+            .crypto()
+            .sha256(&config.symbol.clone().to_xdr(&env))
+            .into();
+        let token = env
+            .deployer()
+            .with_current_contract(salt)
+            .deploy_v2(wasm_hash, (owner.clone(), oracle.clone(), config.clone()));
+
+        let record = TokenRecord {
+            token: token.clone(),
+            owner,
+            oracle,
+            config,
+            deployed_ledger: env.ledger().sequence(),
+        };
+        Self::record_deployment(&env, &record);
+
+        env.events().publish(
+            (Symbol::new(&env, "token_deployed"),),
+            (record.config.symbol, token.clone()),
+        );
+
+        Ok(token)
+    }
+
+    /// Admin sets the WASM hash used for new deployments.
+    pub fn set_wasm_hash(env: Env, wasm_hash: BytesN<32>) -> Result<(), FactoryError> {
+        require_admin(&env)?;
+        env.storage().instance().set(&DataKey::TokenWasm, &wasm_hash);
+        env.events()
+            .publish((Symbol::new(&env, "wasm_hash_set"),), wasm_hash);
+        Ok(())
+    }
+
+    /// Admin upgrades the factory itself.
+    pub fn upgrade(env: Env, wasm_hash: BytesN<32>) -> Result<(), FactoryError> {
+        require_admin(&env)?;
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.deployer().update_current_contract_wasm(wasm_hash);
+        Ok(())
+    }
+
+    // ─── Views ───────────────────────────────────────────────────
+
+    pub fn admin(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Admin)
+    }
+
+    pub fn wasm_hash(env: Env) -> Option<BytesN<32>> {
+        env.storage().instance().get(&DataKey::TokenWasm)
+    }
+
+    /// Number of tokens deployed by this factory.
+    pub fn count(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::DeploymentCount)
+            .unwrap_or(0)
+    }
+
+    /// Registry entry for `symbol`, if deployed.
+    pub fn get_by_symbol(env: Env, symbol: String) -> Option<TokenRecord> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::TokenBySymbol(symbol))
+    }
+
+    /// Deployed token symbols in deployment order, `limit` starting at `start`.
+    pub fn get_symbols(env: Env, start: u32, limit: u32) -> Vec<String> {
+        let end = Self::count(env.clone()).min(start.saturating_add(limit));
+        let mut symbols = Vec::new(&env);
+        for i in start..end {
+            if let Some(symbol) = env.storage().persistent().get(&DataKey::Deployment(i)) {
+                symbols.push_back(symbol);
+            }
+        }
+        symbols
+    }
+
+    // ─── Internal ────────────────────────────────────────────────
+
+    fn record_deployment(env: &Env, record: &TokenRecord) {
+        let index = Self::count(env.clone());
+        let index_key = DataKey::Deployment(index);
+        let symbol_key = DataKey::TokenBySymbol(record.config.symbol.clone());
+        env.storage()
+            .persistent()
+            .set(&index_key, &record.config.symbol);
+        env.storage().persistent().set(&symbol_key, record);
+        env.storage()
+            .persistent()
+            .extend_ttl(&index_key, TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.storage()
+            .persistent()
+            .extend_ttl(&symbol_key, TTL_THRESHOLD, TTL_EXTEND_TO);
+        env.storage()
+            .instance()
+            .set(&DataKey::DeploymentCount, &(index + 1));
+        env.storage()
+            .instance()
+            .extend_ttl(TTL_THRESHOLD, TTL_EXTEND_TO);
+    }
+}
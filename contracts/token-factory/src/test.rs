@@ -0,0 +1,97 @@
+#![cfg(test)]
+
+use super::*;
+use soroban_sdk::testutils::Address as _;
+use soroban_sdk::{vec, Env};
+
+// Built from source by build.rs. Not `contractimport!`: the token's spec
+// repeats type names from the token libraries, so the native client is used.
+const JOULE_TOKEN_WASM: &[u8] =
+    include_bytes!("../../../target/fixtures/wasm32v1-none/release/joule_token.wasm");
+
+fn setup() -> (Env, TokenFactoryClient<'static>, Address, BytesN<32>) {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let wasm_hash = BytesN::from_array(&env, &[1u8; 32]);
+    let contract_id = env.register(TokenFactory, (admin.clone(), wasm_hash.clone()));
+    let client = TokenFactoryClient::new(&env, &contract_id);
+    (env, client, admin, wasm_hash)
+}
+
+fn config(env: &Env) -> TokenConfig {
+    TokenConfig {
+        name: String::from_str(env, "GPU Hour Credit"),
+        symbol: String::from_str(env, "GPUH"),
+        decimals: 7,
+        price_floor: 1_000,
+        price_ceiling: 100_000,
+        mint_cap: 100_000_000_000,
+    }
+}
+
+#[test]
+fn test_constructor() {
+    let (env, client, admin, wasm_hash) = setup();
+    assert_eq!(client.admin(), Some(admin));
+    assert_eq!(client.wasm_hash(), Some(wasm_hash));
+    assert_eq!(client.count(), 0);
+    assert_eq!(client.get_symbols(&0, &10).len(), 0);
+    assert!(client.get_by_symbol(&String::from_str(&env, "JOULE")).is_none());
+}
+
+#[test]
+fn test_set_wasm_hash() {
+    let (env, client, _admin, _) = setup();
+    let new_hash = BytesN::from_array(&env, &[2u8; 32]);
+    client.set_wasm_hash(&new_hash);
+    assert_eq!(client.wasm_hash(), Some(new_hash));
+}
+
+#[test]
+fn test_deploy_real_token() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let owner = Address::generate(&env);
+    let oracle = Address::generate(&env);
+    let wasm_hash = env.deployer().upload_contract_wasm(JOULE_TOKEN_WASM);
+    let contract_id = env.register(TokenFactory, (admin, wasm_hash));
+    let client = TokenFactoryClient::new(&env, &contract_id);
+
+    let token = client.deploy(&owner, &oracle, &config(&env));
+    let symbol = String::from_str(&env, "GPUH");
+    assert_eq!(client.count(), 1);
+    assert_eq!(client.get_symbols(&0, &10), vec![&env, symbol.clone()]);
+    let record = client.get_by_symbol(&symbol).unwrap();
+    assert_eq!(record.token, token);
+    assert_eq!(record.owner, owner);
+    assert_eq!(record.oracle, oracle);
+    assert_eq!(record.deployed_ledger, env.ledger().sequence());
+
+    // Configured by its constructor in the same call
+    let deployed = joule_token::JouleTokenClient::new(&env, &token);
+    assert_eq!(deployed.symbol(), symbol);
+    assert_eq!(deployed.name(), String::from_str(&env, "GPU Hour Credit"));
+    assert_eq!(deployed.decimals(), 7);
+    assert_eq!(deployed.oracle(), oracle);
+    assert_eq!(deployed.mint_cap(), 100_000_000_000);
+
+    assert_eq!(
+        client.try_deploy(&owner, &oracle, &config(&env)),
+        Err(Ok(FactoryError::SymbolTaken))
+    );
+}
+
+#[test]
+#[should_panic(expected = "Error(Auth, InvalidAction)")]
+fn test_deploy_requires_admin() {
+    let env = Env::default();
+    let admin = Address::generate(&env);
+    let contract_id = env.register(
+        TokenFactory,
+        (admin.clone(), BytesN::from_array(&env, &[1u8; 32])),
+    );
+    let client = TokenFactoryClient::new(&env, &contract_id);
+    client.deploy(&admin, &Address::generate(&env), &config(&env));
+}